@@ -0,0 +1,112 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parsed arguments for the enclave-process-facing commands, sent as-is
+//! over the control connection as a [`crate::enclave_proc::codec::Message`]
+//! payload.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::enclave_proc::syslog::SyslogFacility;
+
+/// Arguments for launching an enclave, as parsed from `nitro-cli run-enclave`
+/// and forwarded verbatim to the enclave process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunEnclavesArgs {
+    /// Path to the enclave image file (EIF) to load.
+    pub eif_path: String,
+    /// Number of vCPUs to allocate to the enclave.
+    pub cpu_count: Option<u32>,
+    /// Specific host CPU ids to allocate to the enclave, as an alternative
+    /// to `cpu_count`.
+    pub cpu_ids: Option<Vec<u32>>,
+    /// Amount of memory, in MiB, to allocate to the enclave.
+    pub memory_mib: u64,
+    /// Requested vsock CID; `None` lets the driver assign one.
+    pub enclave_cid: Option<u64>,
+    /// Whether to attach to the enclave's console once it's running.
+    pub debug_mode: bool,
+    /// Upper bound on concurrent enclave launches host-wide; `None` falls
+    /// back to [`crate::enclave_proc::jobserver::default_max_concurrent_launches`].
+    pub max_concurrent_launches: Option<usize>,
+    /// Auto-restart policy applied if the enclave exits unexpectedly.
+    pub restart_policy: RestartPolicy,
+    /// Maximum number of automatic restarts allowed under `restart_policy`.
+    pub restart_max_retries: u32,
+    /// How long to wait before each automatic restart attempt.
+    pub restart_backoff: Duration,
+    /// Where the enclave process's logs are written, beyond its own log
+    /// file.
+    pub log_driver: LogDriver,
+    /// Syslog facility used when `log_driver` is [`LogDriver::Syslog`].
+    pub syslog_facility: SyslogFacility,
+}
+
+/// Auto-restart policy for an enclave that exits without an explicit
+/// `Terminate`, set via `--restart`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RestartPolicy {
+    /// Never restart; report the exit and stop supervising.
+    Never,
+    /// Restart only when the enclave exited on its own (the only case the
+    /// supervisor currently detects).
+    OnFailure,
+    /// Always restart, up to `restart_max_retries`.
+    Always,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(format!("Unknown restart policy: {}", s)),
+        }
+    }
+}
+
+/// Where the enclave process's logs are written, set via `--log-driver`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogDriver {
+    /// Only `EnclaveProcLogWriter`'s own log file.
+    File,
+    /// The log file, plus every line forwarded to the local syslog socket.
+    Syslog,
+}
+
+impl FromStr for LogDriver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(LogDriver::File),
+            "syslog" => Ok(LogDriver::Syslog),
+            _ => Err(format!("Unknown log driver: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_restart_policies() {
+        assert_eq!(RestartPolicy::from_str("never"), Ok(RestartPolicy::Never));
+        assert_eq!(RestartPolicy::from_str("on-failure"), Ok(RestartPolicy::OnFailure));
+        assert_eq!(RestartPolicy::from_str("always"), Ok(RestartPolicy::Always));
+        assert!(RestartPolicy::from_str("sometimes").is_err());
+    }
+
+    #[test]
+    fn parses_known_log_drivers() {
+        assert_eq!(LogDriver::from_str("file"), Ok(LogDriver::File));
+        assert_eq!(LogDriver::from_str("syslog"), Ok(LogDriver::Syslog));
+        assert!(LogDriver::from_str("journald").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The enclave process's log writer: a per-enclave log file, identified by
+//! a logger id that changes as the process launches, restarts or reloads
+//! into a new enclave, plus any number of additional drains (e.g.
+//! [`crate::enclave_proc::syslog::SyslogWriter`]) registered alongside it.
+
+use log::Log;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Directory holding one log file per enclave, named after its logger id.
+const LOG_DIR: &str = "/var/log/nitro_enclaves";
+
+/// Writes formatted log records to a per-enclave file and any additional
+/// registered writers (e.g. a syslog drain). `update_logger_id` and
+/// `add_writer` only need `&self` since every enclave-process thread that
+/// logs holds a shared reference to one instance.
+pub struct EnclaveProcLogWriter {
+    logger_id: Mutex<String>,
+    extra_writers: Mutex<Vec<Box<dyn Write + Send>>>,
+}
+
+impl EnclaveProcLogWriter {
+    /// Create a writer with an initial logger id; the log file for the
+    /// current id is opened, in append mode, each time a record is logged.
+    pub fn new(initial_logger_id: &str) -> Self {
+        EnclaveProcLogWriter {
+            logger_id: Mutex::new(initial_logger_id.to_string()),
+            extra_writers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Switch to a new logger id, e.g. when a fresh enclave is launched,
+    /// restarted or resumed across a `Reload`.
+    pub fn update_logger_id(&self, logger_id: &str) {
+        *self.logger_id.lock().expect("Logger id lock poisoned.") = logger_id.to_string();
+    }
+
+    /// Register an additional writer (e.g. a syslog drain) that every
+    /// subsequent log line is also written to, alongside the log file.
+    pub fn add_writer(&self, writer: Box<dyn Write + Send>) {
+        self.extra_writers
+            .lock()
+            .expect("Extra writers lock poisoned.")
+            .push(writer);
+    }
+
+    fn open_log_file(&self) -> std::io::Result<File> {
+        let logger_id = self.logger_id.lock().expect("Logger id lock poisoned.").clone();
+        std::fs::create_dir_all(LOG_DIR)?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("{}/{}.log", LOG_DIR, logger_id))
+    }
+}
+
+impl Log for EnclaveProcLogWriter {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let line = format!("[{}] {}\n", record.level(), record.args());
+
+        if let Ok(mut file) = self.open_log_file() {
+            let _ = file.write_all(line.as_bytes());
+        }
+
+        for writer in self
+            .extra_writers
+            .lock()
+            .expect("Extra writers lock poisoned.")
+            .iter_mut()
+        {
+            let _ = writer.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {}
+}
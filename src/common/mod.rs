@@ -0,0 +1,69 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Types shared between the `nitro-cli` front-end and the detached enclave
+//! process: the error/exit conventions both sides use, the wire-level
+//! command tags, and the CLI argument/logging/signal plumbing each needs.
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+pub mod commands_parser;
+pub mod logger;
+pub mod signal_handler;
+
+/// The crate-wide `Result` alias: every fallible operation here reports
+/// failure as a human-readable message rather than a structured error
+/// type, since the vast majority of errors are surfaced straight to the
+/// CLI user or the log.
+pub type NitroCliResult<T> = Result<T, String>;
+
+/// Unwrap a `Result`/`Option`, logging `error_msg` and exiting the process
+/// on failure instead of unwinding. Used throughout the enclave process for
+/// conditions that leave it unable to make progress (a broken control
+/// socket, a poisoned lock, a corrupted state file): there is no caller
+/// left to hand a `Result` back to once the event loop itself can't run.
+pub trait ExitGracefully<T, E> {
+    fn ok_or_exit(self, error_msg: &str) -> T;
+}
+
+impl<T, E: std::fmt::Debug> ExitGracefully<T, E> for Result<T, E> {
+    fn ok_or_exit(self, error_msg: &str) -> T {
+        match self {
+            Ok(value) => value,
+            Err(err) => {
+                error!("{}: {:?}", error_msg, err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+impl<T> ExitGracefully<T, ()> for Option<T> {
+    fn ok_or_exit(self, error_msg: &str) -> T {
+        match self {
+            Some(value) => value,
+            None => {
+                error!("{}", error_msg);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// The command tag carried in every framed [`crate::enclave_proc::codec::Message`]
+/// on the `nitro-cli` <-> enclave-process control connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnclaveProcessCommandType {
+    /// Launch the enclave this process manages.
+    Run,
+    /// Tear down the enclave and exit the event loop once complete.
+    Terminate,
+    /// Report the enclave's vsock CID.
+    GetEnclaveCID,
+    /// Report the enclave's current state.
+    Describe,
+    /// Perform a zero-downtime self-upgrade: serialize state, hand off the
+    /// listener/console descriptors and `execve()` the new binary.
+    Reload,
+}
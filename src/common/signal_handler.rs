@@ -0,0 +1,101 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Blocks a set of signals on every thread spawned from the point
+//! `mask_all` runs, then hands them to a single dedicated thread via
+//! `sigwait(2)` instead of an async-signal-unsafe signal handler. That
+//! thread is the only one ever woken by the signal; everyone else keeps
+//! running with it masked.
+
+use log::warn;
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::mem::MaybeUninit;
+use std::thread;
+
+/// Build the raw `sigset_t` containing exactly `signals`.
+fn raw_mask(signals: &[Signal]) -> libc::sigset_t {
+    unsafe {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        for &signal in signals {
+            libc::sigaddset(set.as_mut_ptr(), signal as libc::c_int);
+        }
+        set.assume_init()
+    }
+}
+
+/// A set of signals that can be blocked on the current thread and later
+/// delivered, one at a time, to a dedicated handler thread.
+pub struct SignalHandler {
+    mask: libc::sigset_t,
+}
+
+impl SignalHandler {
+    /// Build a handler for exactly `signals`.
+    pub fn new(signals: &[Signal]) -> Self {
+        SignalHandler {
+            mask: raw_mask(signals),
+        }
+    }
+
+    /// The signals the enclave process cares about: `SIGHUP`, `SIGINT` and
+    /// `SIGTERM`, each of which its handler treats as a shutdown request.
+    pub fn new_with_defaults() -> Self {
+        Self::new(&[Signal::SIGHUP, Signal::SIGINT, Signal::SIGTERM])
+    }
+
+    /// Block this handler's signals on the calling thread. Any thread
+    /// spawned afterwards inherits the same mask (`pthread_sigmask` is
+    /// per-thread and inherited at `pthread_create` time), so only the
+    /// dedicated thread `start_handler` spawns ever has them unblocked.
+    pub fn mask_all(self) -> Self {
+        let rc = unsafe {
+            libc::pthread_sigmask(libc::SIG_BLOCK, &self.mask, std::ptr::null_mut())
+        };
+        if rc != 0 {
+            warn!("Failed to block signals: {}", std::io::Error::from_raw_os_error(rc));
+        }
+        self
+    }
+
+    /// Restore this handler's signals to unblocked on the calling thread.
+    pub fn unmask_all(&self) {
+        let rc = unsafe {
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &self.mask, std::ptr::null_mut())
+        };
+        if rc != 0 {
+            warn!("Failed to unblock signals: {}", std::io::Error::from_raw_os_error(rc));
+        }
+    }
+
+    /// Spawn a dedicated thread that calls `sigwait(2)` on this handler's
+    /// signals in a loop, invoking `handler` with each one it receives.
+    /// `handler` returning `false` stops the thread.
+    pub fn start_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(Signal) -> bool + Send + 'static,
+    {
+        let mask = self.mask;
+        thread::spawn(move || loop {
+            let mut raw_signal: libc::c_int = 0;
+            let rc = unsafe { libc::sigwait(&mask, &mut raw_signal) };
+            if rc != 0 {
+                warn!("sigwait() failed: {}", std::io::Error::from_raw_os_error(rc));
+                break;
+            }
+
+            let signal = match Signal::try_from(raw_signal) {
+                Ok(signal) => signal,
+                Err(_) => {
+                    warn!("sigwait() returned an unrecognized signal: {}", raw_signal);
+                    continue;
+                }
+            };
+
+            if !handler(signal) {
+                break;
+            }
+        });
+    }
+}
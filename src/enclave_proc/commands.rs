@@ -0,0 +1,83 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The three enclave lifecycle operations `process_event_loop` drives:
+//! launching, tearing down and reporting on the enclave this process
+//! manages. The actual CPU/hugepage reservation and NSM/vsock set-up live
+//! in the Nitro Enclaves driver integration below [`super::resource_manager`];
+//! this layer is what the event loop, `reload` and `supervisor` call
+//! directly.
+
+use log::info;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::json_output::EnclaveDescribeInfo;
+use super::resource_manager::EnclaveManager;
+use super::utils::format_enclave_id;
+use crate::common::commands_parser::RunEnclavesArgs;
+use crate::common::NitroCliResult;
+
+/// Vsock CIDs handed out to enclaves launched by this host. Real CID
+/// assignment is negotiated with the NSM device; this is this snapshot's
+/// stand-in source of unique values.
+static NEXT_ENCLAVE_CID: AtomicU64 = AtomicU64::new(100);
+
+/// Launch the enclave described by `run_args`, reserving its resources and
+/// returning the manager that tracks them for the rest of this enclave's
+/// lifetime.
+pub fn run_enclaves(run_args: &mut RunEnclavesArgs) -> NitroCliResult<EnclaveManager> {
+    if !std::path::Path::new(&run_args.eif_path).exists() {
+        return Err(format!("Enclave image file not found: {}", run_args.eif_path));
+    }
+
+    let enclave_cid = run_args
+        .enclave_cid
+        .unwrap_or_else(|| NEXT_ENCLAVE_CID.fetch_add(1, Ordering::Relaxed));
+    let cpu_ids = run_args.cpu_ids.clone().unwrap_or_default();
+    let enclave_id = format_enclave_id(enclave_cid);
+
+    // Stand-in for the enclave's console vsock connection: a connected
+    // socket pair whose far end is handed to `EnclaveManager` and watched
+    // for EOF. The near end is intentionally leaked (never closed) for the
+    // life of the process, so the watched end doesn't see a spurious EOF
+    // the moment this function returns; `terminate_enclaves` tearing the
+    // enclave down is what ends its lifetime in the real driver integration.
+    let (console_ours, console_theirs) =
+        UnixStream::pair().map_err(|e| format!("Failed to create enclave console: {}", e))?;
+    std::mem::forget(console_ours);
+
+    info!("Launching enclave {} (cid {}).", enclave_id, enclave_cid);
+
+    Ok(EnclaveManager::new(
+        enclave_id,
+        enclave_cid,
+        enclave_cid,
+        run_args.memory_mib,
+        cpu_ids,
+        console_theirs.into_raw_fd(),
+    ))
+}
+
+/// Tear down the enclave `enclave_manager` tracks, releasing its CPU and
+/// memory reservation.
+pub fn terminate_enclaves(enclave_manager: &mut EnclaveManager) -> NitroCliResult<()> {
+    info!("Terminating enclave {}.", enclave_manager.enclave_id);
+    unsafe {
+        libc::close(enclave_manager.get_console_fd());
+    }
+    Ok(())
+}
+
+/// Print the current state of the enclave `enclave_manager` tracks.
+pub fn describe_enclaves(enclave_manager: &mut EnclaveManager) -> NitroCliResult<()> {
+    let info = EnclaveDescribeInfo {
+        enclave_id: enclave_manager.enclave_id.clone(),
+        cpu_count: enclave_manager.cpu_ids().len(),
+        memory_mib: enclave_manager.mem_size_mib(),
+        restart_count: enclave_manager.restart_count,
+    };
+    println!("{}", info.to_json());
+    Ok(())
+}
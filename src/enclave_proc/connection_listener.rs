@@ -0,0 +1,390 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Event loop plumbing for the enclave process, built on `mio` instead of
+//! raw epoll. A single `Waker` lets the signal handler thread and the
+//! termination thread deliver control events to the main loop without the
+//! self-connected `UnixStream` pairs the old epoll-based loop relied on.
+
+use log::{info, warn};
+use mio::net::UnixListener as MioUnixListener;
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use nix::sys::signal::Signal;
+use slab::Slab;
+use std::collections::VecDeque;
+use std::io;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+
+use super::connection::Connection;
+use super::resource_manager::EnclaveManager;
+use crate::common::{ExitGracefully, NitroCliResult};
+
+/// Reserved token for the cross-thread `Waker`; every other non-reserved
+/// token is a slab index into `connections`.
+const WAKER_TOKEN: Token = Token(usize::MAX);
+
+/// Reserved token for the enclave's console descriptor, watched for EOF as
+/// a liveness signal.
+const CONSOLE_TOKEN: Token = Token(usize::MAX - 1);
+
+/// Reserved token for the bound CLI listener socket. Kept out of the slab's
+/// own key space (which starts at `0`) so the first connection inserted
+/// into `connections` can never collide with it.
+const LISTENER_TOKEN: Token = Token(usize::MAX - 2);
+
+/// Intent pushed into the event loop by a thread other than the main one,
+/// replacing the old self-connected stream-pair channels.
+#[derive(Debug)]
+pub enum ControlEvent {
+    /// A signal was received and the process should shut down.
+    Signal(Signal),
+    /// Enclave termination finished on the dedicated termination thread.
+    TerminateComplete,
+    /// The listener should stop and the event loop should exit.
+    Stop,
+    /// The enclave's console descriptor hit EOF without a prior
+    /// `Terminate`, meaning the enclave exited on its own.
+    EnclaveExited,
+    /// A restart's backoff timer elapsed and the enclave should now be
+    /// relaunched.
+    RestartReady,
+    /// The token-acquire-and-relaunch work for a restart, run on a
+    /// dedicated thread so it doesn't block the event loop, has produced
+    /// a new `EnclaveManager` for the relaunched enclave.
+    RestartLaunched(EnclaveManager),
+}
+
+/// What a single `poll()` wakeup produced.
+pub enum LoopEvent {
+    /// A CLI connection became readable.
+    Connection(Connection),
+    /// A control event was queued by another thread.
+    Control(ControlEvent),
+}
+
+/// A handle other threads use to push a `ControlEvent` into the main loop
+/// and wake it up, without going through a socket pair.
+#[derive(Clone)]
+pub struct ControlHandle {
+    waker: Arc<Waker>,
+    queue: Arc<Mutex<VecDeque<ControlEvent>>>,
+}
+
+impl ControlHandle {
+    /// Queue `event` for the main loop and wake it immediately.
+    pub fn notify(&self, event: ControlEvent) {
+        self.queue
+            .lock()
+            .expect("Control queue lock poisoned.")
+            .push_back(event);
+        self.waker.wake().ok_or_exit("Failed to wake event loop.");
+    }
+}
+
+/// Drives the enclave process's CLI-facing event loop.
+pub struct ConnectionListener {
+    poll: Poll,
+    events: Events,
+    connections: Slab<UnixStream>,
+    control_handle: ControlHandle,
+    listener: Option<MioUnixListener>,
+    console_fd: Option<RawFd>,
+    /// Connections accepted while draining the listener's edge-triggered
+    /// readiness event, beyond the one returned immediately. Drained before
+    /// polling again so none of them is stranded until unrelated fd churn
+    /// re-fires the edge.
+    pending_connections: VecDeque<Connection>,
+}
+
+impl ConnectionListener {
+    /// Create a listener with a fresh `mio::Poll` and `Waker`, with no
+    /// bound CLI socket yet (`start()` creates that once the enclave ID
+    /// is known).
+    pub fn new() -> Self {
+        let poll = Poll::new().ok_or_exit("Failed to create mio::Poll.");
+        let waker = Arc::new(
+            Waker::new(poll.registry(), WAKER_TOKEN).ok_or_exit("Failed to create mio::Waker."),
+        );
+
+        ConnectionListener {
+            poll,
+            events: Events::with_capacity(128),
+            connections: Slab::new(),
+            control_handle: ControlHandle {
+                waker,
+                queue: Arc::new(Mutex::new(VecDeque::new())),
+            },
+            listener: None,
+            console_fd: None,
+            pending_connections: VecDeque::new(),
+        }
+    }
+
+    /// Rebuild a listener around a socket and a console descriptor that
+    /// were inherited across a binary reload rather than freshly created.
+    /// Re-arms console-EOF liveness supervision on `console_fd` so a reload
+    /// doesn't silently drop it.
+    pub fn from_inherited_fd(listener_fd: RawFd, console_fd: RawFd) -> Self {
+        let mut conn_listener = Self::new();
+        let std_listener = unsafe { UnixListener::from_raw_fd(listener_fd) };
+        std_listener
+            .set_nonblocking(true)
+            .ok_or_exit("Failed to set inherited listener non-blocking.");
+        let mut mio_listener = MioUnixListener::from_std(std_listener);
+
+        conn_listener
+            .poll
+            .registry()
+            .register(&mut mio_listener, LISTENER_TOKEN, Interest::READABLE)
+            .ok_or_exit("Failed to register inherited listener.");
+        conn_listener.listener = Some(mio_listener);
+        conn_listener.watch_console(console_fd);
+        conn_listener
+    }
+
+    /// A clonable handle other threads can use to push control events.
+    pub fn control_handle(&self) -> ControlHandle {
+        self.control_handle.clone()
+    }
+
+    /// Register the initial CLI communication stream (the connection the
+    /// parent `nitro-cli` process used to launch us).
+    pub fn handle_new_connection(&mut self, stream: UnixStream) {
+        stream
+            .set_nonblocking(true)
+            .ok_or_exit("Failed to set stream non-blocking.");
+        let entry = self.connections.vacant_entry();
+        let token = Token(entry.key());
+        let mut mio_stream = mio::net::UnixStream::from_std(
+            stream
+                .try_clone()
+                .ok_or_exit("Failed to clone connection stream."),
+        );
+        self.poll
+            .registry()
+            .register(&mut mio_stream, token, Interest::READABLE)
+            .ok_or_exit("Failed to register connection.");
+        entry.insert(stream);
+    }
+
+    /// Start accepting CLI connections on the enclave's well-known socket.
+    pub fn start(&mut self, enclave_id: &str) -> NitroCliResult<()> {
+        let socket_path = super::socket::enclave_proc_socket_path(enclave_id);
+        if let Some(parent) = socket_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create socket directory: {}", e))?;
+        }
+        let std_listener = UnixListener::bind(&socket_path)
+            .map_err(|e| format!("Failed to bind enclave process socket: {}", e))?;
+        std_listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("Failed to set listener non-blocking: {}", e))?;
+        let mut mio_listener = MioUnixListener::from_std(std_listener);
+
+        self.poll
+            .registry()
+            .register(&mut mio_listener, LISTENER_TOKEN, Interest::READABLE)
+            .map_err(|e| format!("Failed to register listener: {}", e))?;
+        self.listener = Some(mio_listener);
+
+        info!("Connection listener started for enclave {}.", enclave_id);
+        Ok(())
+    }
+
+    /// Start watching the enclave's console descriptor for EOF, which is
+    /// delivered to the main loop as `ControlEvent::EnclaveExited`. Used
+    /// for liveness supervision: a console EOF that wasn't preceded by a
+    /// `Terminate` command means the enclave died on its own.
+    pub fn watch_console(&mut self, console_fd: RawFd) {
+        // Non-blocking, so draining the console in `next_event` stops at
+        // `WouldBlock` instead of stalling the event loop.
+        let flags = unsafe { libc::fcntl(console_fd, libc::F_GETFL) };
+        if flags >= 0 {
+            unsafe { libc::fcntl(console_fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        }
+        self.console_fd = Some(console_fd);
+        self.poll
+            .registry()
+            .register(&mut SourceFd(&console_fd), CONSOLE_TOKEN, Interest::READABLE)
+            .ok_or_exit("Failed to register console fd for liveness supervision.");
+    }
+
+    /// The raw fd of the bound listener, used when handing the socket off
+    /// to a new binary across a reload.
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.listener
+            .as_ref()
+            .expect("Connection listener has not been started.")
+            .as_raw_fd()
+    }
+
+    /// Block until either a CLI connection becomes ready or a control
+    /// event was pushed by another thread.
+    ///
+    /// `mio`'s registrations are edge-triggered, so each branch below must
+    /// drain its fd to `WouldBlock` (or EOF) before returning: accepting or
+    /// reading only once per wakeup can strand a second backlogged
+    /// connection, or a console fd that still has unread bytes, until some
+    /// unrelated fd activity happens to re-fire the edge.
+    pub fn next_event(&mut self) -> LoopEvent {
+        loop {
+            if let Some(event) = self.control_handle.queue.lock().unwrap().pop_front() {
+                return LoopEvent::Control(event);
+            }
+
+            if let Some(connection) = self.pending_connections.pop_front() {
+                return LoopEvent::Connection(connection);
+            }
+
+            self.poll
+                .poll(&mut self.events, None)
+                .ok_or_exit("mio::Poll::poll() failed.");
+
+            for event in self.events.iter() {
+                if event.token() == WAKER_TOKEN {
+                    continue;
+                }
+
+                if event.token() == CONSOLE_TOKEN {
+                    if let Some(fd) = self.console_fd {
+                        let mut buf = [0u8; 4096];
+                        loop {
+                            let n = unsafe {
+                                libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+                            };
+                            if n == 0 {
+                                let _ = self.poll.registry().deregister(&mut SourceFd(&fd));
+                                self.console_fd = None;
+                                return LoopEvent::Control(ControlEvent::EnclaveExited);
+                            }
+                            if n < 0 {
+                                let err = io::Error::last_os_error();
+                                if err.kind() != ErrorKind::WouldBlock {
+                                    warn!("Error reading console fd {}: {}", fd, err);
+                                }
+                                break;
+                            }
+                            // n > 0: drained some console output, not a
+                            // liveness signal; keep draining this edge.
+                        }
+                    }
+                    continue;
+                }
+
+                if event.token() == LISTENER_TOKEN {
+                    if let Some(listener) = &self.listener {
+                        loop {
+                            match listener.accept() {
+                                Ok((stream, _)) => {
+                                    let std_stream = stream
+                                        .into_std()
+                                        .ok_or_exit("Failed to convert accepted connection.");
+                                    // `Connection` uses `read_exact`/`write_all`, which block
+                                    // until the full frame lands rather than retrying on
+                                    // `WouldBlock`; a non-blocking fd here would turn ordinary
+                                    // short reads/writes into spurious, fatal errors.
+                                    std_stream
+                                        .set_nonblocking(false)
+                                        .ok_or_exit("Failed to set accepted connection blocking.");
+                                    self.pending_connections.push_back(Connection::new(std_stream));
+                                }
+                                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                                Err(e) => {
+                                    warn!("Error accepting connection: {}", e);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                let key = event.token().0;
+                if self.connections.contains(key) {
+                    let stream = self.connections.remove(key);
+                    // Same reasoning as the listener-accept path above: hand
+                    // `Connection` a blocking fd so `read_exact`/`write_all`
+                    // can't see a mid-frame `WouldBlock`.
+                    stream
+                        .set_nonblocking(false)
+                        .ok_or_exit("Failed to set connection blocking.");
+                    self.pending_connections.push_back(Connection::new(stream));
+                }
+            }
+
+            if let Some(connection) = self.pending_connections.pop_front() {
+                return LoopEvent::Connection(connection);
+            }
+        }
+    }
+
+    /// Deregister every connection, the listener and the console watch.
+    pub fn stop(&mut self) {
+        if let Some(mut listener) = self.listener.take() {
+            let _ = self.poll.registry().deregister(&mut listener);
+        }
+        if let Some(fd) = self.console_fd.take() {
+            let _ = self.poll.registry().deregister(&mut SourceFd(&fd));
+        }
+        self.connections.clear();
+        self.pending_connections.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::IntoRawFd;
+    use std::thread;
+
+    fn unique_socket_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "nitro_connection_listener_test_{}_{}.sock",
+            std::process::id(),
+            name
+        ))
+    }
+
+    /// Bind a real `UnixListener` and hand its fd (plus a throwaway console
+    /// fd) to `from_inherited_fd`, the same entry point a reloaded process
+    /// uses. Exercises the full accept -> slab/pending-queue hand-off path
+    /// `next_event` drives, against a real socket rather than a mock.
+    #[test]
+    fn next_event_yields_an_accepted_connection_as_blocking() {
+        let socket_path = unique_socket_path("accept_handoff");
+        let _ = std::fs::remove_file(&socket_path);
+        let std_listener =
+            UnixListener::bind(&socket_path).expect("Failed to bind test listener.");
+        let listener_fd = std_listener.into_raw_fd();
+
+        // A throwaway readable fd to stand in for the console descriptor;
+        // this test only exercises the listener/connection path.
+        let (console_read_fd, _console_write_fd) =
+            nix::unistd::pipe().expect("Failed to create console placeholder pipe.");
+
+        let mut conn_listener = ConnectionListener::from_inherited_fd(listener_fd, console_read_fd);
+
+        let connect_path = socket_path.clone();
+        let client = thread::spawn(move || {
+            UnixStream::connect(&connect_path).expect("Failed to connect test client.")
+        });
+
+        let connection = match conn_listener.next_event() {
+            LoopEvent::Connection(connection) => connection,
+            LoopEvent::Control(_) => panic!("Expected an accepted connection, got a control event."),
+        };
+
+        // The whole point of the chunk0-2/chunk0-3 fix: a `Connection` handed
+        // out of the accept path must be blocking, since `recv`/`send` use
+        // `read_exact`/`write_all`, which don't retry on `WouldBlock`.
+        let flags = unsafe { libc::fcntl(connection.as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_NONBLOCK, 0, "accepted connection must be blocking");
+
+        client.join().unwrap();
+        let _ = std::fs::remove_file(&socket_path);
+    }
+}
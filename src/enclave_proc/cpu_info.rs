@@ -0,0 +1,20 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host CPU topology, used to size the jobserver's default launch-token
+//! pool.
+
+use crate::common::NitroCliResult;
+
+/// Number of host CPUs eligible to be handed to an enclave. The real
+/// figure excludes CPU 0 and any sibling hyperthread pinned to housekeeping
+/// (the Nitro Enclaves allocator reserves those); lacking that allocator
+/// integration in this snapshot, this falls back to every CPU `sysconf`
+/// reports.
+pub fn get_enclave_eligible_core_count() -> NitroCliResult<usize> {
+    let count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if count <= 0 {
+        return Err("Failed to determine the number of online CPUs.".to_string());
+    }
+    Ok(count as usize)
+}
@@ -0,0 +1,218 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A host-wide, jobserver-style concurrency gate for enclave launches.
+//!
+//! Several `nitro-cli run-enclave` invocations can race against each other,
+//! and nothing otherwise bounds how many of them try to reserve CPUs and
+//! hugepage memory at the same time. This hands out tokens from a named
+//! FIFO seeded with one token per enclave-eligible core (or the value of
+//! `--max-concurrent-launches`), the same rendezvous pattern a POSIX
+//! jobserver uses: acquiring a token is a single-byte read (non-blocking,
+//! retried every poll interval until a token arrives or a shutdown is
+//! requested), releasing one is a single-byte write.
+
+use log::{info, warn};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::Duration;
+
+use super::cpu_info;
+use crate::common::{ExitGracefully, NitroCliResult};
+
+/// Location of the rendezvous FIFO. All `nitro-cli` enclave processes on
+/// the host share this single gate.
+const JOBSERVER_FIFO_PATH: &str = "/run/nitro_enclaves/jobserver.fifo";
+
+/// How long to wait between polls while acquiring a token, so acquisition
+/// can notice a pending shutdown instead of blocking forever.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static INIT: Once = Once::new();
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Tell any in-progress or future `acquire()` call to give up and return
+/// an `Err` instead of waiting for a token. Called from the signal handler.
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// A held launch slot. Dropping it returns the token to the pool.
+pub struct LaunchToken {
+    released: bool,
+}
+
+impl LaunchToken {
+    /// Explicitly return the token to the pool. Prefer this over relying on
+    /// `Drop` on an early-error path, since `ok_or_exit()` terminates the
+    /// process without running destructors.
+    pub fn release(mut self) {
+        self.do_release();
+    }
+
+    fn do_release(&mut self) {
+        if self.released {
+            return;
+        }
+        self.released = true;
+
+        // Opened read-write, like `ensure_fifo`'s seeding open: a write-only
+        // open of a FIFO blocks until a reader shows up, and `acquire()`
+        // only ever holds the read end open transiently while polling, so
+        // there is no guaranteed concurrent reader to unblock a plain
+        // write-only open here.
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(JOBSERVER_FIFO_PATH)
+        {
+            Ok(mut fifo) => {
+                if let Err(e) = fifo.write_all(&[1u8]) {
+                    warn!("Failed to release jobserver token: {}", e);
+                }
+            }
+            Err(e) => warn!("Failed to open jobserver FIFO for release: {}", e),
+        }
+    }
+}
+
+impl Drop for LaunchToken {
+    fn drop(&mut self) {
+        self.do_release();
+    }
+}
+
+/// Create the rendezvous FIFO and seed it with `capacity` tokens, if it
+/// does not already exist. Idempotent across concurrent first-users.
+fn ensure_fifo(capacity: usize) {
+    ensure_fifo_at(Path::new(JOBSERVER_FIFO_PATH), capacity)
+}
+
+/// `ensure_fifo`, parameterized over the FIFO path so the seeding logic can
+/// be exercised in tests without touching the real, host-wide path.
+fn ensure_fifo_at(path: &Path, capacity: usize) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).ok_or_exit("Failed to create jobserver run directory.");
+    }
+
+    match nix::unistd::mkfifo(path, nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR)
+    {
+        Ok(()) => {
+            info!("Created jobserver FIFO with {} launch tokens.", capacity);
+            // Open read-write so the seeding write below cannot block on a
+            // reader, then immediately seed the requested number of tokens.
+            let mut fifo = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path)
+                .ok_or_exit("Failed to open freshly created jobserver FIFO.");
+            fifo.write_all(&vec![1u8; capacity])
+                .ok_or_exit("Failed to seed jobserver tokens.");
+            // A FIFO's buffered bytes are discarded once nobody holds it
+            // open, so keep this descriptor open for the life of the
+            // process instead of dropping the tokens just written.
+            std::mem::forget(fifo);
+        }
+        Err(nix::errno::Errno::EEXIST) => {
+            // Another process already created and seeded the FIFO.
+        }
+        Err(e) => Err::<(), _>(e).ok_or_exit("Failed to create jobserver FIFO."),
+    }
+}
+
+/// The default pool size: one launch token per enclave-eligible core.
+pub fn default_max_concurrent_launches() -> usize {
+    cpu_info::get_enclave_eligible_core_count()
+        .ok_or_exit("Failed to determine enclave-eligible core count.")
+        .max(1)
+}
+
+/// Block until a launch token is available, or a shutdown was requested
+/// via [`request_shutdown`], in which case an `Err` is returned.
+pub fn acquire(max_concurrent_launches: usize) -> NitroCliResult<LaunchToken> {
+    INIT.call_once(|| ensure_fifo(max_concurrent_launches));
+
+    loop {
+        if SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+            return Err("Interrupted while waiting for a launch slot.".to_string());
+        }
+
+        let mut fifo = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(JOBSERVER_FIFO_PATH)
+            .ok_or_exit("Failed to open jobserver FIFO.");
+
+        let mut token = [0u8; 1];
+        match fifo.read(&mut token) {
+            Ok(1) => {
+                return Ok(LaunchToken { released: false });
+            }
+            Ok(_) => std::thread::sleep(POLL_INTERVAL),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(POLL_INTERVAL)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => Err::<(), _>(e).ok_or_exit("Failed to read jobserver token."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path under the OS temp dir, unique per test run, standing in for
+    /// the real host-wide `JOBSERVER_FIFO_PATH`.
+    fn unique_fifo_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("nitro_jobserver_test_{}_{}", std::process::id(), name))
+    }
+
+    fn read_all_tokens(path: &Path) -> Vec<u8> {
+        let mut fifo = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .expect("Failed to open test FIFO for reading.");
+        let mut tokens = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            match fifo.read(&mut byte) {
+                Ok(1) => tokens.push(byte[0]),
+                Ok(_) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("Failed to read test FIFO: {}", e),
+            }
+        }
+        tokens
+    }
+
+    #[test]
+    fn seeds_exactly_capacity_tokens() {
+        let path = unique_fifo_path("seeds_exactly_capacity_tokens");
+        let _ = std::fs::remove_file(&path);
+
+        ensure_fifo_at(&path, 3);
+        assert_eq!(read_all_tokens(&path), vec![1u8; 3]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn is_idempotent_for_an_already_seeded_fifo() {
+        let path = unique_fifo_path("is_idempotent_for_an_already_seeded_fifo");
+        let _ = std::fs::remove_file(&path);
+
+        ensure_fifo_at(&path, 2);
+        // A second call must not re-seed on top of the existing FIFO.
+        ensure_fifo_at(&path, 2);
+        assert_eq!(read_all_tokens(&path), vec![1u8; 2]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
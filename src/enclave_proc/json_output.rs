@@ -0,0 +1,28 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The JSON shape `nitro-cli describe-enclaves` prints, kept as one typed
+//! struct so `commands::describe_enclaves` can't drift out of sync with
+//! itself across fields the way hand-built JSON strings tend to.
+
+use serde::Serialize;
+
+/// One enclave's reported state.
+#[derive(Serialize)]
+pub struct EnclaveDescribeInfo {
+    #[serde(rename = "EnclaveID")]
+    pub enclave_id: String,
+    #[serde(rename = "CPUCount")]
+    pub cpu_count: usize,
+    #[serde(rename = "MemoryMiB")]
+    pub memory_mib: u64,
+    #[serde(rename = "RestartCount")]
+    pub restart_count: u32,
+}
+
+impl EnclaveDescribeInfo {
+    /// Render as the single-line JSON `describe-enclaves` prints.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| format!("{{\"error\": \"{}\"}}", e))
+    }
+}
@@ -2,54 +2,41 @@
 // SPDX-License-Identifier: Apache-2.0
 #![deny(warnings)]
 
+pub mod codec;
 pub mod commands;
 pub mod connection;
 pub mod connection_listener;
 pub mod cpu_info;
+pub mod jobserver;
 pub mod json_output;
+pub mod reload;
 pub mod resource_manager;
 pub mod socket;
+pub mod supervisor;
+pub mod syslog;
 pub mod utils;
 
 use log::{info, warn};
 use nix::sys::signal::{Signal, SIGHUP};
 use nix::unistd::*;
 use procinfo::pid;
-use serde::de::DeserializeOwned;
 use std::fs::OpenOptions;
-use std::io::{self, Read};
-use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::UnixStream;
 use std::process;
 use std::thread::{self, JoinHandle};
 
-use super::common::MSG_ENCLAVE_CONFIRM;
-use super::common::{
-    enclave_proc_command_send_single, read_u64_le, receive_command_type, write_u64_le,
-};
 use super::common::{EnclaveProcessCommandType, ExitGracefully, NitroCliResult};
-use crate::common::commands_parser::{EmptyArgs, RunEnclavesArgs};
+use crate::common::commands_parser::{LogDriver, RunEnclavesArgs};
 use crate::common::logger::EnclaveProcLogWriter;
 use crate::common::signal_handler::SignalHandler;
 
+use codec::Ack;
 use commands::{describe_enclaves, run_enclaves, terminate_enclaves};
 use connection::Connection;
-use connection_listener::ConnectionListener;
+use connection_listener::{ConnectionListener, ControlEvent, ControlHandle, LoopEvent};
 use resource_manager::EnclaveManager;
 
-/// Read the arguments of the CLI command.
-fn receive_command_args<T>(input_stream: &mut dyn Read) -> io::Result<T>
-where
-    T: DeserializeOwned,
-{
-    let arg_size = read_u64_le(input_stream)? as usize;
-    let mut arg_data: Vec<u8> = vec![0; arg_size];
-    input_stream.read_exact(&mut arg_data[..])?;
-    let args: T = serde_cbor::from_slice(&arg_data[..])
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    Ok(args)
-}
-
 /// Route STDOUT and STDERR also to the CLI socket. Also provide
 /// the old (common) descriptor used previously by both.
 fn route_output_to(fd: RawFd) -> RawFd {
@@ -82,18 +69,10 @@ fn get_logger_id(enclave_id: &str) -> String {
     format!("enc-{}", tokens[0])
 }
 
-fn send_command_and_close(cmd: &EnclaveProcessCommandType, stream: &mut UnixStream) {
-    enclave_proc_command_send_single::<EmptyArgs>(cmd, None, stream)
-        .ok_or_exit("Failed to send command.");
-    stream
-        .shutdown(std::net::Shutdown::Both)
-        .ok_or_exit("Failed to shut down stream.");
-}
-
 /// Perform enclave termination.
 fn run_terminate(
     connection: Connection,
-    mut thread_stream: UnixStream,
+    control_handle: ControlHandle,
     mut enclave_manager: EnclaveManager,
 ) {
     safe_route_output(
@@ -103,11 +82,8 @@ fn run_terminate(
     )
     .ok_or_exit("Failed to terminate enclave.");
 
-    // Notify the main thread that enclave termination has completed.
-    send_command_and_close(
-        &EnclaveProcessCommandType::TerminateComplete,
-        &mut thread_stream,
-    );
+    // Notify the main loop that enclave termination has completed.
+    control_handle.notify(ControlEvent::TerminateComplete);
 }
 
 /// Start enclave termination.
@@ -116,44 +92,79 @@ fn notify_terminate(
     conn_listener: &ConnectionListener,
     enclave_manager: EnclaveManager,
 ) -> Option<JoinHandle<()>> {
-    let (local_stream, thread_stream) =
-        UnixStream::pair().ok_or_exit("Failed to create stream pair.");
-
-    conn_listener.add_stream_to_epoll(local_stream);
+    let control_handle = conn_listener.control_handle();
     Some(thread::spawn(move || {
-        run_terminate(connection, thread_stream, enclave_manager)
+        run_terminate(connection, control_handle, enclave_manager)
     }))
 }
 
 fn enclave_proc_configure_signal_handler(conn_listener: &ConnectionListener) {
     let mut signal_handler = SignalHandler::new_with_defaults().mask_all();
-    let (local_stream, thread_stream) =
-        UnixStream::pair().ok_or_exit("Failed to create stream pair.");
+    let control_handle = conn_listener.control_handle();
 
-    conn_listener.add_stream_to_epoll(local_stream);
-    signal_handler.start_handler(thread_stream.into_raw_fd(), enclave_proc_handle_signals);
+    signal_handler.start_handler(move |signal| enclave_proc_handle_signals(&control_handle, signal));
 }
 
-fn enclave_proc_handle_signals(comm_fd: RawFd, signal: Signal) -> bool {
-    let mut stream = unsafe { UnixStream::from_raw_fd(comm_fd) };
-
+fn enclave_proc_handle_signals(control_handle: &ControlHandle, signal: Signal) -> bool {
     warn!(
         "Received signal {:?}. The enclave process will now close.",
         signal
     );
-    send_command_and_close(
-        &EnclaveProcessCommandType::ConnectionListenerStop,
-        &mut stream,
-    );
+    // Unblock a launch that may be waiting on a jobserver token so the
+    // process can exit promptly instead of hanging until one frees up.
+    jobserver::request_shutdown();
+    control_handle.notify(ControlEvent::Stop);
 
     true
 }
 
 /// The main event loop of the enclave process.
 fn process_event_loop(comm_stream: UnixStream, logger: &EnclaveProcLogWriter) {
-    let mut conn_listener = ConnectionListener::new();
-    let mut enclave_manager = EnclaveManager::default();
+    let mut last_run_args: Option<RunEnclavesArgs> = None;
+    let mut restart_state: Option<supervisor::RestartState> = None;
+
+    let (mut conn_listener, mut enclave_manager) = match reload::inherited_fds() {
+        Some(fds) => {
+            info!("Resuming after reload, inheriting listener={} console={}.", fds.listener, fds.console);
+            let inherited = reload::inherited_state()
+                .ok_or_exit("Failed to restore enclave manager state after reload.");
+            logger.update_logger_id(&get_logger_id(&inherited.enclave_manager.enclave_id));
+
+            // The snapshot carries no descriptors: rebuild the manager around
+            // the inherited console fd and the restart count carried with it.
+            let restored = &inherited.enclave_manager;
+            let mut resumed_manager = EnclaveManager::new(
+                restored.enclave_id.clone(),
+                restored.get_console_resources().ok_or_exit("Failed to restore enclave CID."),
+                restored.slot_uid(),
+                restored.mem_size_mib(),
+                restored.cpu_ids(),
+                fds.console,
+            );
+            resumed_manager.restart_count = inherited.restart_attempts;
+
+            // Re-arm the restart policy from before the reload so liveness
+            // supervision (chunk0-5) keeps working across a self-upgrade
+            // instead of being silently reset.
+            if let Some(run_args) = &inherited.run_args {
+                restart_state = Some(supervisor::RestartState::with_attempts(
+                    run_args.restart_policy,
+                    run_args.restart_max_retries,
+                    run_args.restart_backoff,
+                    inherited.restart_attempts,
+                ));
+            }
+            last_run_args = inherited.run_args;
+
+            (
+                ConnectionListener::from_inherited_fd(fds.listener, fds.console),
+                resumed_manager,
+            )
+        }
+        None => (ConnectionListener::new(), EnclaveManager::default()),
+    };
     let mut terminate_thread: Option<std::thread::JoinHandle<()>> = None;
+    let mut terminating = false;
 
     // Start the signal handler before spawning any other threads. This is done since the
     // handler will mask all relevant signals from the current thread and this setting will
@@ -161,66 +172,203 @@ fn process_event_loop(comm_stream: UnixStream, logger: &EnclaveProcLogWriter) {
     // because only the dedicated thread spawned by the handler should listen for signals.
     enclave_proc_configure_signal_handler(&conn_listener);
 
-    // Add the CLI communication channel to epoll.
+    // Register the CLI communication channel with the event loop.
     conn_listener.handle_new_connection(comm_stream);
 
     loop {
-        // We can get connections to CLI instances, to the resource driver or to ourselves.
-        let mut connection = Connection::new(conn_listener.get_epoll_fd());
-        let cmd =
-            receive_command_type(connection.as_reader()).ok_or_exit("Failed to receive command.");
-        info!("Received command: {:?}", cmd);
+        // Each wakeup yields either a CLI connection or a control event pushed
+        // by the signal handler or termination thread through the `Waker`.
+        let mut connection = match conn_listener.next_event() {
+            LoopEvent::Connection(connection) => connection,
+            LoopEvent::Control(ControlEvent::Stop) => break,
+            LoopEvent::Control(ControlEvent::TerminateComplete) => {
+                info!("Enclave has completed termination.");
+                match terminate_thread.take() {
+                    Some(handle) => handle
+                        .join()
+                        .ok_or_exit("Failed to retrieve termination thread."),
+                    None => warn!("Received termination confirmation on an invalid thread handle."),
+                };
+                break;
+            }
+            LoopEvent::Control(ControlEvent::Signal(signal)) => {
+                warn!("Received signal {:?} on the main loop.", signal);
+                break;
+            }
+            LoopEvent::Control(ControlEvent::EnclaveExited) => {
+                if terminating {
+                    // Expected: the console closes as part of an in-progress
+                    // `Terminate`, which is already tracked separately.
+                    continue;
+                }
+
+                warn!("Enclave {} exited unexpectedly.", enclave_manager.enclave_id);
+                let can_restart = restart_state
+                    .as_ref()
+                    .map(|state| state.should_restart())
+                    .unwrap_or(false);
+
+                if !can_restart || last_run_args.is_none() {
+                    warn!("Not restarting enclave {}; exiting event loop.", enclave_manager.enclave_id);
+                    break;
+                }
+
+                let restart_state = restart_state.as_mut().unwrap();
+                let attempt = restart_state.record_restart();
+                let backoff = restart_state.backoff();
+                info!("Restarting enclave {} (attempt {}) after {:?}.", enclave_manager.enclave_id, attempt, backoff);
+
+                // Wait out the backoff on a dedicated thread instead of
+                // blocking the event loop thread with `thread::sleep`, so a
+                // `Terminate`, new connection or shutdown signal arriving
+                // during the backoff window is still handled promptly.
+                let control_handle = conn_listener.control_handle();
+                thread::spawn(move || {
+                    thread::sleep(backoff);
+                    control_handle.notify(ControlEvent::RestartReady);
+                });
+                continue;
+            }
+            LoopEvent::Control(ControlEvent::RestartReady) => {
+                if terminating {
+                    // A `Terminate` arrived while the backoff timer for this
+                    // restart was still running; don't relaunch an enclave
+                    // the user already asked to tear down.
+                    continue;
+                }
+
+                let run_args = last_run_args.clone().unwrap();
+                // Acquire the launch token and relaunch on a dedicated
+                // thread rather than the event loop thread, same as the
+                // backoff sleep above: `Terminate`/`Describe` (and a new
+                // connection) must stay responsive while this blocks on a
+                // launch slot and `run_enclaves` itself does its work.
+                let control_handle = conn_listener.control_handle();
+                thread::spawn(move || {
+                    let mut run_args = run_args;
+                    let max_concurrent_launches = run_args
+                        .max_concurrent_launches
+                        .unwrap_or_else(jobserver::default_max_concurrent_launches);
+                    let launch_token = jobserver::acquire(max_concurrent_launches)
+                        .ok_or_exit("Interrupted while waiting for a launch slot.");
+
+                    let run_result = run_enclaves(&mut run_args);
+                    launch_token.release();
+                    let enclave_manager =
+                        run_result.ok_or_exit("Failed to restart enclave after unexpected exit.");
+                    control_handle.notify(ControlEvent::RestartLaunched(enclave_manager));
+                });
+                continue;
+            }
+            LoopEvent::Control(ControlEvent::RestartLaunched(mut new_enclave_manager)) => {
+                if terminating {
+                    // A `Terminate` arrived while the relaunch above was in
+                    // flight; tear the freshly launched enclave straight
+                    // back down instead of letting it run unsupervised.
+                    let _ = terminate_enclaves(&mut new_enclave_manager);
+                    continue;
+                }
+
+                // The old listener socket is about to be replaced by one
+                // bound at the new enclave id's path; unlink its file so a
+                // crash-loop restart doesn't leak one stale socket per
+                // attempt.
+                let _ = std::fs::remove_file(socket::enclave_proc_socket_path(
+                    &enclave_manager.enclave_id,
+                ));
+
+                new_enclave_manager.restart_count = restart_state
+                    .as_ref()
+                    .map(|state| state.attempts())
+                    .unwrap_or(0);
+                enclave_manager = new_enclave_manager;
 
-        match cmd {
+                info!("Enclave ID = {}", enclave_manager.enclave_id);
+                logger.update_logger_id(&get_logger_id(&enclave_manager.enclave_id));
+                conn_listener
+                    .start(&enclave_manager.enclave_id)
+                    .ok_or_exit("Failed to start connection listener.");
+                conn_listener.watch_console(enclave_manager.get_console_fd());
+                continue;
+            }
+        };
+
+        let message = connection.recv().ok_or_exit("Failed to receive command.");
+        info!("Received command: {:?} (request {})", message.command, message.request_id);
+
+        match message.command {
             EnclaveProcessCommandType::Run => {
-                let mut run_args = receive_command_args::<RunEnclavesArgs>(connection.as_reader())
+                let mut run_args = message
+                    .payload_as::<RunEnclavesArgs>()
                     .ok_or_exit("Failed to get run arguments.");
                 info!("Run args = {:?}", run_args);
 
-                enclave_manager =
+                let max_concurrent_launches = run_args
+                    .max_concurrent_launches
+                    .unwrap_or_else(jobserver::default_max_concurrent_launches);
+                let launch_token = jobserver::acquire(max_concurrent_launches)
+                    .ok_or_exit("Interrupted while waiting for a launch slot.");
+
+                let run_result =
                     safe_route_output(&mut run_args, connection.as_raw_fd(), |mut run_args| {
                         run_enclaves(&mut run_args)
-                    })
-                    .ok_or_exit("Failed to run enclave.");
+                    });
+                launch_token.release();
+                enclave_manager = run_result.ok_or_exit("Failed to run enclave.");
 
                 info!("Enclave ID = {}", enclave_manager.enclave_id);
                 logger.update_logger_id(&get_logger_id(&enclave_manager.enclave_id));
                 conn_listener
                     .start(&enclave_manager.enclave_id)
                     .ok_or_exit("Failed to start connection listener.");
+                conn_listener.watch_console(enclave_manager.get_console_fd());
+
+                if run_args.log_driver == LogDriver::Syslog {
+                    // `--log-driver syslog` is an optional extra drain, not
+                    // a requirement for the enclave to run: a host with no
+                    // `/dev/log` (common in minimal/containerized setups)
+                    // should still launch successfully, just without the
+                    // syslog copy of its logs.
+                    match syslog::SyslogWriter::connect(&enclave_manager.enclave_id, run_args.syslog_facility) {
+                        Ok(syslog_writer) => logger.add_writer(Box::new(syslog_writer)),
+                        Err(e) => warn!("Failed to connect syslog drain, falling back to file-only logging: {}", e),
+                    }
+                }
+
+                restart_state = Some(supervisor::RestartState::new(
+                    run_args.restart_policy,
+                    run_args.restart_max_retries,
+                    run_args.restart_backoff,
+                ));
+                last_run_args = Some(run_args);
 
                 // TODO: run_enclaves(run_args).ok_or_exit(args.usage());
             }
 
             EnclaveProcessCommandType::Terminate => {
+                terminating = true;
                 terminate_thread =
                     notify_terminate(connection, &conn_listener, enclave_manager.clone());
 
                 //TODO: terminate_enclaves(terminate_args).ok_or_exit(args.usage());
             }
 
-            EnclaveProcessCommandType::TerminateComplete => {
-                info!("Enclave has completed termination.");
-                match terminate_thread {
-                    Some(handle) => handle
-                        .join()
-                        .ok_or_exit("Failed to retrieve termination thread."),
-                    None => warn!("Received termination confirmation on an invalid thread handle."),
-                };
-
-                break;
-            }
-
             EnclaveProcessCommandType::GetEnclaveCID => {
                 let enclave_cid = enclave_manager
                     .get_console_resources()
                     .ok_or_exit("Failed to get enclave CID.");
-                write_u64_le(connection.as_writer(), enclave_cid)
+                connection
+                    .reply(
+                        message.request_id,
+                        EnclaveProcessCommandType::GetEnclaveCID,
+                        &enclave_cid,
+                    )
                     .ok_or_exit("Failed to send enclave CID.");
             }
 
             EnclaveProcessCommandType::Describe => {
-                write_u64_le(connection.as_writer(), MSG_ENCLAVE_CONFIRM)
+                connection
+                    .reply(message.request_id, EnclaveProcessCommandType::Describe, &Ack)
                     .ok_or_exit("Failed to write confirmation.");
 
                 safe_route_output(
@@ -233,7 +381,24 @@ fn process_event_loop(comm_stream: UnixStream, logger: &EnclaveProcLogWriter) {
                 //TODO: describe_enclaves(describe_args).ok_or_exit(args.usage());
             }
 
-            EnclaveProcessCommandType::ConnectionListenerStop => break,
+            EnclaveProcessCommandType::Reload => {
+                // Ack before the execve() below replaces this process: it's
+                // the caller's only way to tell "reload is under way" from
+                // "the enclave process crashed", since both otherwise just
+                // look like the socket resetting.
+                connection
+                    .reply(message.request_id, EnclaveProcessCommandType::Reload, &Ack)
+                    .ok_or_exit("Failed to ack reload.");
+
+                let restart_attempts = restart_state.as_ref().map(|state| state.attempts()).unwrap_or(0);
+                reload::perform_reload(
+                    &conn_listener,
+                    &enclave_manager,
+                    last_run_args.as_ref(),
+                    restart_attempts,
+                )
+                .ok_or_exit("Failed to reload enclave process.");
+            }
         };
     }
 
@@ -304,7 +469,11 @@ fn create_enclave_process() {
 /// * `logger` - The current log writer, whose ID gets updated when an enclave is launched.
 pub fn enclave_process_run(comm_stream: UnixStream, logger: &EnclaveProcLogWriter) -> i32 {
     logger.update_logger_id("enc-xxxxxxxxxxxx");
-    create_enclave_process();
+    // A process resuming after a `Reload` is already detached and already owns
+    // an active enclave, so it must not re-run the daemonization dance.
+    if reload::inherited_fds().is_none() {
+        create_enclave_process();
+    }
     process_event_loop(comm_stream, logger);
 
     0
@@ -0,0 +1,238 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for replacing the running enclave process binary in place while
+//! leaving the listening socket and the enclave's vsock/console descriptors
+//! untouched, so a host-side upgrade never disturbs a running enclave.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::RawFd;
+use std::process;
+
+use super::connection_listener::ConnectionListener;
+use super::resource_manager::EnclaveManager;
+use crate::common::commands_parser::RunEnclavesArgs;
+use crate::common::{ExitGracefully, NitroCliResult};
+
+/// Name of the environment variable used to pass inherited descriptors
+/// across an `execve()`-based reload.
+pub const NITRO_INHERIT_FDS_ENV: &str = "NITRO_INHERIT_FDS";
+
+/// Name of the environment variable pointing to the file holding the
+/// serialized enclave manager state that survives the reload.
+const NITRO_INHERIT_STATE_ENV: &str = "NITRO_INHERIT_STATE";
+
+/// The subset of `EnclaveManager` state that must survive a binary reload,
+/// since the enclave itself is never stopped. Also carries enough of the
+/// liveness-supervision state (the original launch args and how many
+/// restarts have already happened) that chunk0-5's auto-restart policy and
+/// console-EOF watch can be re-armed by the reloaded process instead of
+/// being silently dropped.
+#[derive(Serialize, Deserialize)]
+struct EnclaveManagerSnapshot {
+    enclave_id: String,
+    enclave_cid: u64,
+    slot_uid: u64,
+    mem_size_mib: u64,
+    cpu_ids: Vec<u32>,
+    run_args: Option<RunEnclavesArgs>,
+    restart_attempts: u32,
+}
+
+impl EnclaveManagerSnapshot {
+    fn capture(
+        enclave_manager: &EnclaveManager,
+        run_args: Option<&RunEnclavesArgs>,
+        restart_attempts: u32,
+    ) -> NitroCliResult<Self> {
+        Ok(EnclaveManagerSnapshot {
+            enclave_id: enclave_manager.enclave_id.clone(),
+            enclave_cid: enclave_manager.get_console_resources()?,
+            slot_uid: enclave_manager.slot_uid(),
+            mem_size_mib: enclave_manager.mem_size_mib(),
+            cpu_ids: enclave_manager.cpu_ids(),
+            run_args: run_args.cloned(),
+            restart_attempts,
+        })
+    }
+}
+
+/// Everything a reloaded process needs to resume where the pre-reload one
+/// left off: the running enclave's manager plus its liveness-supervision
+/// state.
+pub struct InheritedState {
+    pub enclave_manager: EnclaveManager,
+    pub run_args: Option<RunEnclavesArgs>,
+    pub restart_attempts: u32,
+}
+
+/// Clear `FD_CLOEXEC` on `fd` so it survives the upcoming `execve()`.
+fn clear_cloexec(fd: RawFd) -> NitroCliResult<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(format!("Failed to read flags for fd {}.", fd));
+    }
+    let rc = unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) };
+    if rc < 0 {
+        return Err(format!("Failed to clear FD_CLOEXEC on fd {}.", fd));
+    }
+    Ok(())
+}
+
+/// Perform a graceful self-upgrade: serialize the current enclave manager
+/// state, hand the listening socket and the enclave's console descriptors
+/// down to the new binary and `execve()` over the current process.
+///
+/// This never touches the running enclave: the NSM device, its vsock and
+/// its console stay open across the `execve()`, so a measured, already
+/// running enclave is left completely undisturbed by the upgrade.
+pub fn perform_reload(
+    conn_listener: &ConnectionListener,
+    enclave_manager: &EnclaveManager,
+    run_args: Option<&RunEnclavesArgs>,
+    restart_attempts: u32,
+) -> NitroCliResult<()> {
+    info!("Starting self-reload for enclave {}.", enclave_manager.enclave_id);
+
+    let snapshot = EnclaveManagerSnapshot::capture(enclave_manager, run_args, restart_attempts)?;
+    let state_path = format!("/tmp/.nitro_reload_{}.state", process::id());
+    // `create_new` refuses to follow a pre-existing path, including one
+    // planted as a symlink ahead of time: the process id in `state_path` is
+    // guessable, so a plain `.create(true).truncate(true)` would let a
+    // local user pre-plant a symlink there and have us overwrite whatever
+    // it points to. Mode 0o600 then keeps the snapshot's contents private
+    // to us once the file exists.
+    let mut state_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&state_path)
+        .map_err(|e| format!("Failed to create reload state file: {}", e))?;
+    let state_json = serde_json::to_vec(&snapshot)
+        .map_err(|e| format!("Failed to serialize enclave manager state: {}", e))?;
+    state_file
+        .write_all(&state_json)
+        .map_err(|e| format!("Failed to write reload state file: {}", e))?;
+
+    let listener_fd = conn_listener.as_raw_fd();
+    let console_fd = enclave_manager.get_console_fd();
+
+    clear_cloexec(listener_fd)?;
+    clear_cloexec(console_fd)?;
+
+    let exe = env::current_exe().map_err(|e| format!("Failed to get current executable: {}", e))?;
+    let exe_cstr = CString::new(exe.to_string_lossy().as_bytes())
+        .map_err(|e| format!("Invalid executable path: {}", e))?;
+    let args: Vec<CString> = env::args()
+        .map(|arg| CString::new(arg).expect("Argument contains a NUL byte."))
+        .collect();
+
+    env::set_var(
+        NITRO_INHERIT_FDS_ENV,
+        format!("listener={},console={}", listener_fd, console_fd),
+    );
+    env::set_var(NITRO_INHERIT_STATE_ENV, &state_path);
+
+    info!("Exec-ing new binary, handing off listener={} console={}.", listener_fd, console_fd);
+    nix::unistd::execv(&exe_cstr, &args)
+        .map_err(|e| format!("execve() failed during reload: {}", e))?;
+
+    unreachable!("execve() only returns on error, which is handled above.");
+}
+
+/// Descriptors inherited from a parent process across a reload `execve()`.
+pub struct InheritedFds {
+    pub listener: RawFd,
+    pub console: RawFd,
+}
+
+/// Parse the `key=value,...` shape `perform_reload` writes into
+/// `NITRO_INHERIT_FDS_ENV`. Factored out of `inherited_fds` so the parsing
+/// itself can be exercised without touching the real environment.
+fn parse_inherited_fds(raw: &str) -> Option<InheritedFds> {
+    let mut listener = None;
+    let mut console = None;
+
+    for entry in raw.split(',') {
+        let mut parts = entry.splitn(2, '=');
+        let (key, value) = (parts.next()?, parts.next()?);
+        let fd: RawFd = value.parse().ok()?;
+        match key {
+            "listener" => listener = Some(fd),
+            "console" => console = Some(fd),
+            _ => (),
+        }
+    }
+
+    Some(InheritedFds {
+        listener: listener?,
+        console: console?,
+    })
+}
+
+/// Parse `NITRO_INHERIT_FDS_ENV` if this process was started as the target
+/// of a reload, returning the descriptors the parent left open for us.
+pub fn inherited_fds() -> Option<InheritedFds> {
+    let raw = env::var(NITRO_INHERIT_FDS_ENV).ok()?;
+    parse_inherited_fds(&raw)
+}
+
+/// Read back the enclave manager and liveness-supervision state left
+/// behind by the process that `execve()`-d into us, if any.
+pub fn inherited_state() -> Option<InheritedState> {
+    let state_path = env::var(NITRO_INHERIT_STATE_ENV).ok()?;
+    let data = std::fs::read(&state_path).ok_or_exit("Failed to read reload state file.");
+    let snapshot: EnclaveManagerSnapshot = serde_json::from_slice(&data)
+        .ok_or_exit("Failed to deserialize reload state file.");
+    let _ = std::fs::remove_file(&state_path);
+
+    Some(InheritedState {
+        enclave_manager: EnclaveManager::from_reload_snapshot(
+            snapshot.enclave_id,
+            snapshot.enclave_cid,
+            snapshot.slot_uid,
+            snapshot.mem_size_mib,
+            snapshot.cpu_ids,
+        ),
+        run_args: snapshot.run_args,
+        restart_attempts: snapshot.restart_attempts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_inherited_fds_round_trips_through_the_format_perform_reload_writes() {
+        let raw = format!("listener={},console={}", 5, 7);
+        let fds = parse_inherited_fds(&raw).expect("Failed to parse a well-formed fd list.");
+        assert_eq!(fds.listener, 5);
+        assert_eq!(fds.console, 7);
+    }
+
+    #[test]
+    fn parse_inherited_fds_is_order_independent() {
+        let fds = parse_inherited_fds("console=7,listener=5").unwrap();
+        assert_eq!(fds.listener, 5);
+        assert_eq!(fds.console, 7);
+    }
+
+    #[test]
+    fn parse_inherited_fds_rejects_a_missing_key() {
+        assert!(parse_inherited_fds("listener=5").is_none());
+        assert!(parse_inherited_fds("console=7").is_none());
+    }
+
+    #[test]
+    fn parse_inherited_fds_rejects_malformed_entries() {
+        assert!(parse_inherited_fds("listener=5,console=not-a-number").is_none());
+        assert!(parse_inherited_fds("garbage").is_none());
+    }
+}
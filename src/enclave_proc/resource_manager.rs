@@ -0,0 +1,120 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tracks the resources (vsock CID, console descriptor, CPU/memory
+//! reservation) backing a single running enclave, and is what survives a
+//! `Reload` or an auto-restart.
+//!
+//! The real CPU/hugepage reservation and NSM device handling live below
+//! this layer, in the Nitro Enclaves driver integration; `EnclaveManager`
+//! here is the state `process_event_loop`, `reload` and `supervisor`
+//! actually need to hold onto across those boundaries.
+
+use std::os::unix::io::RawFd;
+
+use crate::common::NitroCliResult;
+
+/// The state of a single enclave this process is responsible for.
+#[derive(Debug, Clone)]
+pub struct EnclaveManager {
+    /// Full enclave id, e.g. `i-0123456789abcdef0-enc0123456789abcdef0`.
+    pub enclave_id: String,
+    /// Number of times this enclave has been automatically restarted after
+    /// exiting unexpectedly. Reported by `describe_enclaves` and carried
+    /// across a `Reload` in [`super::reload::InheritedState`].
+    pub restart_count: u32,
+    enclave_cid: u64,
+    slot_uid: u64,
+    mem_size_mib: u64,
+    cpu_ids: Vec<u32>,
+    console_fd: RawFd,
+}
+
+impl Default for EnclaveManager {
+    /// A placeholder instance held before the first `Run` (or the restore
+    /// from a `Reload`) replaces it with a real one.
+    fn default() -> Self {
+        EnclaveManager {
+            enclave_id: String::new(),
+            restart_count: 0,
+            enclave_cid: 0,
+            slot_uid: 0,
+            mem_size_mib: 0,
+            cpu_ids: Vec::new(),
+            console_fd: -1,
+        }
+    }
+}
+
+impl EnclaveManager {
+    /// Build a manager for a freshly launched enclave.
+    pub fn new(
+        enclave_id: String,
+        enclave_cid: u64,
+        slot_uid: u64,
+        mem_size_mib: u64,
+        cpu_ids: Vec<u32>,
+        console_fd: RawFd,
+    ) -> Self {
+        EnclaveManager {
+            enclave_id,
+            restart_count: 0,
+            enclave_cid,
+            slot_uid,
+            mem_size_mib,
+            cpu_ids,
+            console_fd,
+        }
+    }
+
+    /// Rebuild a manager for an enclave that was already running before a
+    /// `Reload`; the enclave itself was never touched, only the state
+    /// describing it needed to be carried across the `execve()`.
+    pub fn from_reload_snapshot(
+        enclave_id: String,
+        enclave_cid: u64,
+        slot_uid: u64,
+        mem_size_mib: u64,
+        cpu_ids: Vec<u32>,
+    ) -> Self {
+        // The console descriptor itself is restored separately, from the
+        // inherited fd rather than the serialized snapshot (see
+        // `reload::inherited_fds`); it isn't meaningful to reconstruct here.
+        EnclaveManager {
+            enclave_id,
+            restart_count: 0,
+            enclave_cid,
+            slot_uid,
+            mem_size_mib,
+            cpu_ids,
+            console_fd: -1,
+        }
+    }
+
+    /// The enclave's vsock CID, used to answer `GetEnclaveCID` and as part
+    /// of the state snapshotted across a `Reload`.
+    pub fn get_console_resources(&self) -> NitroCliResult<u64> {
+        Ok(self.enclave_cid)
+    }
+
+    /// The enclave's console descriptor, watched for EOF as a liveness
+    /// signal and handed off across a `Reload`.
+    pub fn get_console_fd(&self) -> RawFd {
+        self.console_fd
+    }
+
+    /// The host slot this enclave's resources are reserved under.
+    pub fn slot_uid(&self) -> u64 {
+        self.slot_uid
+    }
+
+    /// Memory, in MiB, reserved for this enclave.
+    pub fn mem_size_mib(&self) -> u64 {
+        self.mem_size_mib
+    }
+
+    /// Host CPU ids reserved for this enclave.
+    pub fn cpu_ids(&self) -> Vec<u32> {
+        self.cpu_ids.clone()
+    }
+}
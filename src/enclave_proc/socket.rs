@@ -0,0 +1,16 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Well-known locations for the per-enclave CLI control sockets.
+
+use std::path::PathBuf;
+
+/// Directory holding one control socket per running enclave, named after
+/// its enclave id.
+const SOCKET_DIR: &str = "/run/nitro_enclaves";
+
+/// Path of the control socket `nitro-cli` connects to in order to reach
+/// the enclave process managing `enclave_id`.
+pub fn enclave_proc_socket_path(enclave_id: &str) -> PathBuf {
+    PathBuf::from(SOCKET_DIR).join(format!("{}.sock", enclave_id))
+}
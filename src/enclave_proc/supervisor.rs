@@ -0,0 +1,114 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Restart policy bookkeeping for enclave liveness supervision.
+//!
+//! `process_event_loop` previously only reacted to an explicit `Terminate`
+//! command; if the enclave itself died (a panic inside it, an NSM reset, a
+//! driver eviction), nothing noticed and the slot leaked. This tracks how
+//! many times an unexpectedly-exited enclave has been relaunched, and
+//! decides whether another relaunch is still allowed under the configured
+//! `--restart` policy.
+
+use std::time::Duration;
+
+use crate::common::commands_parser::RestartPolicy;
+
+/// Tracks restart attempts for a single enclave across its lifetime.
+pub struct RestartState {
+    policy: RestartPolicy,
+    max_retries: u32,
+    backoff: Duration,
+    attempts: u32,
+}
+
+impl RestartState {
+    /// Build the restart state carried in `RunEnclavesArgs`.
+    pub fn new(policy: RestartPolicy, max_retries: u32, backoff: Duration) -> Self {
+        Self::with_attempts(policy, max_retries, backoff, 0)
+    }
+
+    /// Build restart state that already has `attempts` restarts counted
+    /// against it, e.g. when resuming supervision across a `Reload`.
+    pub fn with_attempts(
+        policy: RestartPolicy,
+        max_retries: u32,
+        backoff: Duration,
+        attempts: u32,
+    ) -> Self {
+        RestartState {
+            policy,
+            max_retries,
+            backoff,
+            attempts,
+        }
+    }
+
+    /// Whether another relaunch is allowed after the enclave exited
+    /// unexpectedly (i.e. without going through the `Terminate` command).
+    pub fn should_restart(&self) -> bool {
+        match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure | RestartPolicy::Always => self.attempts < self.max_retries,
+        }
+    }
+
+    /// Record that a relaunch is about to happen, returning the new
+    /// attempt count.
+    pub fn record_restart(&mut self) -> u32 {
+        self.attempts += 1;
+        self.attempts
+    }
+
+    /// How many times the enclave has been relaunched so far.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// How long to wait before the next relaunch attempt.
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_policy_never_restarts() {
+        let state = RestartState::new(RestartPolicy::Never, 5, Duration::from_secs(1));
+        assert!(!state.should_restart());
+    }
+
+    #[test]
+    fn restarts_until_max_retries_then_stops() {
+        let mut state = RestartState::new(RestartPolicy::OnFailure, 2, Duration::from_secs(1));
+        assert!(state.should_restart());
+        assert_eq!(state.record_restart(), 1);
+        assert!(state.should_restart());
+        assert_eq!(state.record_restart(), 2);
+        assert!(!state.should_restart());
+    }
+
+    #[test]
+    fn always_policy_behaves_like_on_failure_against_max_retries() {
+        let mut state = RestartState::new(RestartPolicy::Always, 1, Duration::from_secs(1));
+        assert!(state.should_restart());
+        state.record_restart();
+        assert!(!state.should_restart());
+    }
+
+    #[test]
+    fn with_attempts_resumes_from_a_prior_count() {
+        let state = RestartState::with_attempts(RestartPolicy::OnFailure, 3, Duration::from_secs(1), 2);
+        assert_eq!(state.attempts(), 2);
+        assert!(state.should_restart());
+    }
+
+    #[test]
+    fn backoff_returns_configured_duration() {
+        let state = RestartState::new(RestartPolicy::Always, 1, Duration::from_millis(250));
+        assert_eq!(state.backoff(), Duration::from_millis(250));
+    }
+}
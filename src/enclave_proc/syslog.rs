@@ -0,0 +1,145 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A syslog drain for the enclave process logger.
+//!
+//! Once `create_enclave_process` daemonizes and `hide_standard_descriptors`
+//! redirects stdio to `/dev/null`, the log file written by
+//! `EnclaveProcLogWriter` is the only durable record of what happened.
+//! This gives operators an alternative: a drain that also writes every log
+//! line to the local syslog socket (`/dev/log`), tagged with the enclave
+//! ID, so entries land in journald/rsyslog correlated with everything
+//! else on the host.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+
+/// Path of the local syslog socket on virtually every Linux distribution.
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+
+/// Syslog facility codes relevant to a CLI-launched daemon (RFC 5424,
+/// numeric values multiplied by 8 to leave room for the severity).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyslogFacility {
+    User,
+    Daemon,
+    Local0,
+    Local1,
+}
+
+impl SyslogFacility {
+    fn code(self) -> i32 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+        }
+    }
+}
+
+impl std::str::FromStr for SyslogFacility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(SyslogFacility::User),
+            "daemon" => Ok(SyslogFacility::Daemon),
+            "local0" => Ok(SyslogFacility::Local0),
+            "local1" => Ok(SyslogFacility::Local1),
+            _ => Err(format!("Unknown syslog facility: {}", s)),
+        }
+    }
+}
+
+/// Severity applied to every line this drain forwards. `info!`/`warn!`
+/// output from the enclave process already carries its own level prefix
+/// in the formatted line, so the drain uses a single fixed severity
+/// (`LOG_INFO`) for the syslog priority field itself.
+const SEVERITY_INFO: i32 = 6;
+
+/// A `Write` implementation that forwards each non-empty line of a write
+/// as its own syslog datagram, so it can be plugged in next to `EnclaveProcLogWriter`'s file
+/// output wherever that type accepts additional writers.
+pub struct SyslogWriter {
+    socket: UnixDatagram,
+    facility: SyslogFacility,
+    /// Tag identifying this enclave in the resulting syslog entries.
+    ident: String,
+}
+
+impl SyslogWriter {
+    /// Connect a new drain to the local syslog socket, tagged with
+    /// `enclave_id`.
+    pub fn connect(enclave_id: &str, facility: SyslogFacility) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SYSLOG_SOCKET_PATH)?;
+
+        Ok(SyslogWriter {
+            socket,
+            facility,
+            ident: enclave_id.to_string(),
+        })
+    }
+
+    fn priority(&self) -> i32 {
+        self.facility.code() * 8 + SEVERITY_INFO
+    }
+}
+
+impl Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            let message = format!(
+                "<{}>nitro-enclaves-cli[{}]: {}",
+                self.priority(),
+                self.ident,
+                String::from_utf8_lossy(line)
+            );
+            self.socket.send(message.as_bytes())?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn parses_known_facilities() {
+        assert_eq!(SyslogFacility::from_str("user"), Ok(SyslogFacility::User));
+        assert_eq!(SyslogFacility::from_str("daemon"), Ok(SyslogFacility::Daemon));
+        assert_eq!(SyslogFacility::from_str("local0"), Ok(SyslogFacility::Local0));
+        assert_eq!(SyslogFacility::from_str("local1"), Ok(SyslogFacility::Local1));
+    }
+
+    #[test]
+    fn rejects_unknown_facility() {
+        assert!(SyslogFacility::from_str("local7").is_err());
+    }
+
+    #[test]
+    fn facility_codes_match_rfc_5424() {
+        assert_eq!(SyslogFacility::User.code(), 1);
+        assert_eq!(SyslogFacility::Daemon.code(), 3);
+        assert_eq!(SyslogFacility::Local0.code(), 16);
+        assert_eq!(SyslogFacility::Local1.code(), 17);
+    }
+
+    #[test]
+    fn priority_combines_facility_and_severity() {
+        let writer = SyslogWriter {
+            socket: UnixDatagram::unbound().unwrap(),
+            facility: SyslogFacility::Local0,
+            ident: "test".to_string(),
+        };
+        assert_eq!(writer.priority(), 16 * 8 + SEVERITY_INFO);
+    }
+}
@@ -0,0 +1,24 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Small helpers shared across the enclave-process modules.
+
+/// Build the full enclave id used for the socket path, the log file name
+/// and every `EnclaveID` reported back to the CLI, from the vsock CID
+/// assigned to it. `get_logger_id` (in the parent module) recovers the
+/// trailing `<cid>` half of this same id after a launch or a restart.
+pub fn format_enclave_id(enclave_cid: u64) -> String {
+    format!("i-{:016x}-enc{:016x}", enclave_cid, enclave_cid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_enclave_id_is_recoverable_by_get_logger_id() {
+        let id = format_enclave_id(42);
+        let tokens: Vec<_> = id.rsplit("-enc").collect();
+        assert_eq!(tokens[0], format!("{:016x}", 42));
+    }
+}